@@ -3,7 +3,8 @@ use std::collections::HashMap;
 use std::mem;
 
 use bril_rs::{Argument, Code, EffectOps, Function, Instruction, Position};
-use petgraph::{graph::NodeIndex, Graph};
+use petgraph::visit::EdgeRef;
+use petgraph::{graph::NodeIndex, Direction, Graph};
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub(crate) enum BlockName {
@@ -19,7 +20,7 @@ pub(crate) struct BasicBlock {
 }
 
 impl BasicBlock {
-    fn empty(name: BlockName) -> BasicBlock {
+    pub(crate) fn empty(name: BlockName) -> BasicBlock {
         BasicBlock {
             instrs: Default::default(),
             name,
@@ -51,6 +52,212 @@ pub(crate) struct Cfg {
     pub(crate) exit: NodeIndex,
 }
 
+impl Cfg {
+    /// Render this CFG as Graphviz DOT, for dumping at each stage of the
+    /// lowering pipeline (original, restructured, ...) and diffing against a
+    /// golden file when something in that pipeline regresses.
+    pub(crate) fn to_dot(&self) -> String {
+        let mut out = String::new();
+        out.push_str("digraph cfg {\n");
+        for node in self.graph.node_indices() {
+            let block = self.graph.node_weight(node).unwrap();
+            let shape = match block.name {
+                BlockName::Entry | BlockName::Exit => "doublecircle",
+                BlockName::Named(_) => "box",
+            };
+            out.push_str(&format!(
+                "    {} [shape={}, label=\"{}\"];\n",
+                node.index(),
+                shape,
+                block_label(block),
+            ));
+        }
+        for edge in self.graph.edge_indices() {
+            let (src, dst) = self.graph.edge_endpoints(edge).unwrap();
+            let branch = self.graph.edge_weight(edge).unwrap();
+            out.push_str(&format!(
+                "    {} -> {} [label=\"{}\"];\n",
+                src.index(),
+                dst.index(),
+                branch_label(branch),
+            ));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Shrink the graph `to_cfg` produced: merge a block into its unique
+    /// predecessor whenever they're connected by nothing but a plain jump,
+    /// and elide empty blocks that only exist to forward one. `to_cfg`
+    /// creates plenty of both - every label introduces a block even when it
+    /// falls straight through to the next, and `get_index` allocates a
+    /// block before its body (if any) is known - and leaving them in place
+    /// means restructuring and dominator analysis waste time on spurious
+    /// single-instruction regions.
+    pub(crate) fn simplify(&mut self) {
+        loop {
+            let merged = self.merge_straight_line_blocks();
+            let elided = self.elide_empty_jump_blocks();
+            if !merged && !elided {
+                break;
+            }
+        }
+    }
+
+    /// Fold `b` into `a` whenever `a -> b` is a plain jump, `b` is `a`'s only
+    /// successor, and `a` is `b`'s only predecessor. Runs to a fixpoint.
+    fn merge_straight_line_blocks(&mut self) -> bool {
+        let mut changed = false;
+        while let Some((pred, succ)) = self.find_straight_line_pair() {
+            let edge = self.graph.find_edge(pred, succ).unwrap();
+            self.graph.remove_edge(edge);
+
+            let mut tail = mem::take(&mut self.graph.node_weight_mut(succ).unwrap().instrs);
+            self.graph
+                .node_weight_mut(pred)
+                .unwrap()
+                .instrs
+                .append(&mut tail);
+
+            let outgoing: Vec<_> = self
+                .graph
+                .edges_directed(succ, Direction::Outgoing)
+                .map(|e| e.id())
+                .collect();
+            for out_edge in outgoing {
+                let (_, target) = self.graph.edge_endpoints(out_edge).unwrap();
+                let branch = self.graph.remove_edge(out_edge).unwrap();
+                self.graph.add_edge(pred, target, branch);
+            }
+
+            self.remove_node_fixing_indices(succ);
+            changed = true;
+        }
+        changed
+    }
+
+    fn find_straight_line_pair(&self) -> Option<(NodeIndex, NodeIndex)> {
+        for succ in self.graph.node_indices() {
+            if succ == self.entry || succ == self.exit {
+                continue;
+            }
+            let mut preds = self.graph.neighbors_directed(succ, Direction::Incoming);
+            let Some(pred) = preds.next() else {
+                continue;
+            };
+            if preds.next().is_some() || pred == succ {
+                continue;
+            }
+            let mut pred_succs = self.graph.neighbors_directed(pred, Direction::Outgoing);
+            let Some(only) = pred_succs.next() else {
+                continue;
+            };
+            if pred_succs.next().is_some() || only != succ {
+                continue;
+            }
+            let edge = self.graph.find_edge(pred, succ).unwrap();
+            if self.graph.edge_weight(edge).unwrap().op == BranchOp::Jmp {
+                return Some((pred, succ));
+            }
+        }
+        None
+    }
+
+    /// Remove an empty block whose only role is to forward an unconditional
+    /// jump, rewiring its incoming edges directly to its target and keeping
+    /// each incoming edge's own `BranchOp`/`Position` (so a `Cond` or
+    /// `RetVal` edge stays a `Cond`/`RetVal` edge). Runs to a fixpoint.
+    fn elide_empty_jump_blocks(&mut self) -> bool {
+        let mut changed = false;
+        while let Some(node) = self.find_empty_jump_block() {
+            let mut outgoing = self.graph.edges_directed(node, Direction::Outgoing);
+            let out_edge = outgoing.next().unwrap();
+            let target = out_edge.target();
+            let out_id = out_edge.id();
+            drop(outgoing);
+            self.graph.remove_edge(out_id);
+
+            let incoming: Vec<_> = self
+                .graph
+                .edges_directed(node, Direction::Incoming)
+                .map(|e| (e.source(), e.id()))
+                .collect();
+            for (src, in_id) in incoming {
+                let branch = self.graph.remove_edge(in_id).unwrap();
+                self.graph.add_edge(src, target, branch);
+            }
+
+            self.remove_node_fixing_indices(node);
+            changed = true;
+        }
+        changed
+    }
+
+    fn find_empty_jump_block(&self) -> Option<NodeIndex> {
+        for node in self.graph.node_indices() {
+            if node == self.entry || node == self.exit {
+                continue;
+            }
+            if !self.graph.node_weight(node).unwrap().instrs.is_empty() {
+                continue;
+            }
+            let mut outs = self.graph.edges_directed(node, Direction::Outgoing);
+            let Some(out_edge) = outs.next() else {
+                continue;
+            };
+            if outs.next().is_some() || out_edge.target() == node {
+                continue;
+            }
+            if out_edge.weight().op == BranchOp::Jmp {
+                return Some(node);
+            }
+        }
+        None
+    }
+
+    /// `petgraph::Graph::remove_node` swaps the last node into the removed
+    /// slot, which can silently invalidate `self.entry`/`self.exit`; fix
+    /// them up if either was the node that got moved.
+    fn remove_node_fixing_indices(&mut self, node: NodeIndex) {
+        let last = NodeIndex::new(self.graph.node_count() - 1);
+        self.graph.remove_node(node);
+        if last != node {
+            if self.entry == last {
+                self.entry = node;
+            }
+            if self.exit == last {
+                self.exit = node;
+            }
+        }
+    }
+}
+
+fn block_label(block: &BasicBlock) -> String {
+    let name = match &block.name {
+        BlockName::Entry => "entry".to_string(),
+        BlockName::Exit => "exit".to_string(),
+        BlockName::Named(name) => name.clone(),
+    };
+    let mut lines = vec![dot_escape(&name)];
+    lines.extend(block.instrs.iter().map(|instr| dot_escape(&instr.to_string())));
+    // `\l` is Graphviz's left-justified line break; trailing one keeps the
+    // last instruction left-justified too.
+    format!("{}\\l", lines.join("\\l"))
+}
+
+fn branch_label(branch: &Branch) -> String {
+    let label = match &branch.op {
+        BranchOp::Jmp => "jmp".to_string(),
+        BranchOp::Cond { arg, val } => format!("{arg} == {val}"),
+        BranchOp::RetVal { arg } => format!("ret {arg}"),
+    };
+    dot_escape(&label)
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
 struct CfgBuilder {
     cfg: Cfg,
     label_to_block: HashMap<String, NodeIndex>,
@@ -236,4 +443,159 @@ pub(crate) fn to_cfg(func: &Function) -> Cfg {
     }
     builder.finish_block(current, mem::take(&mut block));
     builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `to_dot` should at least mention every block and every branch kind by
+    /// name, so a reviewer can spot a missing node or mislabeled edge without
+    /// running `dot`.
+    #[test]
+    fn to_dot_mentions_blocks_and_branches() {
+        let mut graph = Graph::default();
+        let entry = graph.add_node(BasicBlock::empty(BlockName::Entry));
+        let body = graph.add_node(BasicBlock::empty(BlockName::Named("body".into())));
+        let exit = graph.add_node(BasicBlock::empty(BlockName::Exit));
+        graph.add_edge(
+            entry,
+            body,
+            Branch {
+                op: BranchOp::Cond {
+                    arg: "cond".into(),
+                    val: true,
+                },
+                pos: None,
+            },
+        );
+        graph.add_edge(
+            body,
+            exit,
+            Branch {
+                op: BranchOp::RetVal { arg: "x".into() },
+                pos: None,
+            },
+        );
+        let cfg = Cfg {
+            args: Vec::new(),
+            graph,
+            entry,
+            exit,
+        };
+        let dot = cfg.to_dot();
+        assert!(dot.starts_with("digraph cfg {"));
+        assert!(dot.contains("body"));
+        assert!(dot.contains("cond == true"));
+        assert!(dot.contains("ret x"));
+    }
+
+    fn jmp() -> Branch {
+        Branch {
+            op: BranchOp::Jmp,
+            pos: None,
+        }
+    }
+
+    /// entry -> a -> b -> exit, each linked by a single plain jump: `a` and
+    /// `b` should both fold into `entry`, leaving just entry and exit.
+    #[test]
+    fn simplify_merges_straight_line_blocks() {
+        let mut graph = Graph::default();
+        let entry = graph.add_node(BasicBlock::empty(BlockName::Entry));
+        let a = graph.add_node(BasicBlock {
+            instrs: vec![],
+            name: BlockName::Named("a".into()),
+            pos: None,
+        });
+        let b = graph.add_node(BasicBlock {
+            instrs: vec![],
+            name: BlockName::Named("b".into()),
+            pos: None,
+        });
+        let exit = graph.add_node(BasicBlock::empty(BlockName::Exit));
+        graph.add_edge(entry, a, jmp());
+        graph.add_edge(a, b, jmp());
+        graph.add_edge(b, exit, jmp());
+        let mut cfg = Cfg {
+            args: Vec::new(),
+            graph,
+            entry,
+            exit,
+        };
+
+        cfg.simplify();
+
+        assert_eq!(cfg.graph.node_count(), 2);
+        let edge = cfg.graph.find_edge(cfg.entry, cfg.exit);
+        assert!(edge.is_some(), "entry should still reach exit directly");
+    }
+
+    /// entry branches on `cond` to either an empty jump-only block or
+    /// straight to `join`; eliding the empty block must preserve the `Cond`
+    /// edge (not turn it into a plain `Jmp`). `join` returns a value rather
+    /// than jumping to exit, so it is not itself an elidable block.
+    #[test]
+    fn simplify_elides_empty_block_preserving_cond() {
+        let mut graph = Graph::default();
+        let entry = graph.add_node(BasicBlock::empty(BlockName::Entry));
+        let empty = graph.add_node(BasicBlock::empty(BlockName::Named("empty".into())));
+        let join = graph.add_node(BasicBlock::empty(BlockName::Named("join".into())));
+        let exit = graph.add_node(BasicBlock::empty(BlockName::Exit));
+        graph.add_edge(
+            entry,
+            empty,
+            Branch {
+                op: BranchOp::Cond {
+                    arg: "cond".into(),
+                    val: true,
+                },
+                pos: None,
+            },
+        );
+        graph.add_edge(
+            entry,
+            join,
+            Branch {
+                op: BranchOp::Cond {
+                    arg: "cond".into(),
+                    val: false,
+                },
+                pos: None,
+            },
+        );
+        graph.add_edge(empty, join, jmp());
+        graph.add_edge(
+            join,
+            exit,
+            Branch {
+                op: BranchOp::RetVal { arg: "x".into() },
+                pos: None,
+            },
+        );
+        let mut cfg = Cfg {
+            args: Vec::new(),
+            graph,
+            entry,
+            exit,
+        };
+
+        cfg.simplify();
+
+        assert!(!cfg
+            .graph
+            .node_indices()
+            .any(|n| matches!(&cfg.graph[n].name, BlockName::Named(name) if name == "empty")));
+        let entry_to_join_ops: Vec<_> = cfg
+            .graph
+            .edges_connecting(cfg.entry, join)
+            .map(|e| &e.weight().op)
+            .collect();
+        assert!(entry_to_join_ops
+            .iter()
+            .any(|op| matches!(op, BranchOp::Cond { arg, val: true } if arg == "cond")));
+        assert!(entry_to_join_ops
+            .iter()
+            .any(|op| matches!(op, BranchOp::Cond { arg, val: false } if arg == "cond")));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,162 @@
+//! Dominator tree and natural-loop analysis over a `Cfg`.
+//!
+//! RVSDG conversion needs to know, for any two blocks, whether one
+//! dominates the other (to decide what belongs inside a gamma node) and
+//! which blocks form the natural loop behind a back edge (to decide what
+//! belongs inside a theta node). Both queries are built once here rather
+//! than recomputed ad hoc at every call site, so the conversion - and tests
+//! that pin it down against known Bril loops - can rely on a single
+//! well-tested API.
+use std::collections::{HashMap, HashSet};
+
+use petgraph::algo::dominators::{self, Dominators};
+use petgraph::graph::NodeIndex;
+use petgraph::Direction;
+
+use super::cfg::Cfg;
+
+/// The dominator tree of a `Cfg`, rooted at its entry block.
+pub(crate) struct DomTree {
+    dominators: Dominators<NodeIndex>,
+}
+
+impl DomTree {
+    pub(crate) fn new(cfg: &Cfg) -> DomTree {
+        DomTree {
+            dominators: dominators::simple_fast(&cfg.graph, cfg.entry),
+        }
+    }
+
+    /// Does `a` dominate `b`? A node is considered to dominate itself.
+    pub(crate) fn dominates(&self, a: NodeIndex, b: NodeIndex) -> bool {
+        if a == b {
+            return true;
+        }
+        self.dominators
+            .strict_dominators(b)
+            .map(|mut ds| ds.any(|d| d == a))
+            .unwrap_or(false)
+    }
+
+    /// The immediate dominator of `node`, or `None` for the entry block
+    /// (which has no dominator) or for unreachable blocks.
+    pub(crate) fn immediate_dominator(&self, node: NodeIndex) -> Option<NodeIndex> {
+        self.dominators.immediate_dominator(node)
+    }
+}
+
+/// A single natural loop: the set of blocks that can reach the back edge's
+/// source without passing through the header, plus the header itself.
+pub(crate) struct NaturalLoop {
+    pub(crate) header: NodeIndex,
+    pub(crate) body: HashSet<NodeIndex>,
+}
+
+/// All of the natural loops in a `Cfg`, keyed by their header.
+pub(crate) struct LoopForest {
+    loops: HashMap<NodeIndex, NaturalLoop>,
+}
+
+impl LoopForest {
+    /// Given `cfg` and its dominator tree, find every back edge (an edge
+    /// `u -> v` where `v` dominates `u`) and compute its natural loop.
+    pub(crate) fn new(cfg: &Cfg, dom: &DomTree) -> LoopForest {
+        let mut loops: HashMap<NodeIndex, NaturalLoop> = HashMap::new();
+        for edge in cfg.graph.edge_indices() {
+            let (u, v) = cfg.graph.edge_endpoints(edge).unwrap();
+            if !dom.dominates(v, u) {
+                continue;
+            }
+            let body = natural_loop_body(cfg, u, v);
+            loops
+                .entry(v)
+                .and_modify(|existing| existing.body.extend(body.iter().copied()))
+                .or_insert(NaturalLoop { header: v, body });
+        }
+        LoopForest { loops }
+    }
+
+    /// The header of the natural loop containing `node`, if any. When a
+    /// block is nested in several loops, this is the innermost one: the
+    /// header whose body is the smallest set containing `node`.
+    pub(crate) fn loop_header_of(&self, node: NodeIndex) -> Option<NodeIndex> {
+        self.loops
+            .values()
+            .filter(|l| l.body.contains(&node))
+            .min_by_key(|l| l.body.len())
+            .map(|l| l.header)
+    }
+
+    /// The set of blocks in the natural loop headed by `header`, if any.
+    pub(crate) fn loop_body(&self, header: NodeIndex) -> Option<&HashSet<NodeIndex>> {
+        self.loops.get(&header).map(|l| &l.body)
+    }
+}
+
+/// Collect every node that can reach `tail` (the back edge's source)
+/// without passing through `header`, plus `header` itself.
+fn natural_loop_body(cfg: &Cfg, tail: NodeIndex, header: NodeIndex) -> HashSet<NodeIndex> {
+    let mut body = HashSet::new();
+    body.insert(header);
+    if tail == header {
+        return body;
+    }
+    let mut worklist = vec![tail];
+    body.insert(tail);
+    while let Some(node) = worklist.pop() {
+        for pred in cfg.graph.neighbors_directed(node, Direction::Incoming) {
+            if body.insert(pred) {
+                worklist.push(pred);
+            }
+        }
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rvsdg::cfg::{BasicBlock, BlockName, Branch, BranchOp};
+
+    fn jmp() -> Branch {
+        Branch {
+            op: BranchOp::Jmp,
+            pos: None,
+        }
+    }
+
+    /// entry -> head -> body -> head (back edge), body -> exit
+    #[test]
+    fn finds_simple_loop() {
+        let mut graph = petgraph::Graph::new();
+        let entry = graph.add_node(BasicBlock::empty(BlockName::Entry));
+        let head = graph.add_node(BasicBlock::empty(BlockName::Named("head".into())));
+        let body = graph.add_node(BasicBlock::empty(BlockName::Named("body".into())));
+        let exit = graph.add_node(BasicBlock::empty(BlockName::Exit));
+        graph.add_edge(entry, head, jmp());
+        graph.add_edge(head, body, jmp());
+        graph.add_edge(body, head, jmp());
+        graph.add_edge(body, exit, jmp());
+        let cfg = Cfg {
+            args: Vec::new(),
+            graph,
+            entry,
+            exit,
+        };
+
+        let dom = DomTree::new(&cfg);
+        assert!(dom.dominates(entry, body));
+        assert!(dom.dominates(head, body));
+        assert!(!dom.dominates(body, head));
+
+        let loops = LoopForest::new(&cfg, &dom);
+        assert_eq!(loops.loop_header_of(body), Some(head));
+        assert_eq!(loops.loop_header_of(head), Some(head));
+        assert_eq!(loops.loop_header_of(entry), None);
+        let body_set = loops.loop_body(head).unwrap();
+        assert!(body_set.contains(&head));
+        assert!(body_set.contains(&body));
+        assert!(!body_set.contains(&entry));
+        assert!(!body_set.contains(&exit));
+    }
+}
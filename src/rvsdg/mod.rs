@@ -17,6 +17,9 @@
 //! RVSDGs. Part of this conversion process is the discovery of what the
 //! "inputs" and "outputs" are for different RVSDG nodes.
 pub(crate) mod cfg;
+pub(crate) mod dom;
+pub(crate) mod restructure;
+pub(crate) mod sese;
 
 #[cfg(test)]
 mod tests;
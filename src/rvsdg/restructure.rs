@@ -0,0 +1,383 @@
+//! Restructure an irreducible `Cfg` into a reducible one.
+//!
+//! RVSDG conversion needs every loop in the `Cfg` to have a single entry and
+//! a single exit: that is what lets a loop become a single theta node. Bril
+//! programs are under no such obligation, so before we attempt RVSDG
+//! conversion we rewrite any loop that is entered at more than one of its
+//! blocks (or exits to more than one block) into an equivalent loop with a
+//! single entry/exit, guarded by a
+//! synthesized "selector" variable that records which of the original
+//! targets the control flow was actually headed for.
+use std::collections::HashSet;
+
+use bril_rs::{ConstOps, Instruction, Literal, Type};
+use petgraph::{algo::kosaraju_scc, graph::NodeIndex, visit::EdgeRef, Direction};
+
+use super::cfg::{BasicBlock, BlockName, Branch, BranchOp, Cfg};
+
+const ENTRY_SELECTOR: &str = "__entry_selector";
+const EXIT_SELECTOR: &str = "__exit_selector";
+
+/// Rewrite `cfg` in place so that every loop has a single block through
+/// which all of the loop's entries are dispatched, and a single block
+/// through which all of the loop's exits are dispatched. Back edges are not
+/// merged: a loop can still have several back edges into its header.
+pub(crate) fn restructure(cfg: &mut Cfg) {
+    let mut counter = 0;
+    loop {
+        let Some(members) = next_irreducible_loop(cfg) else {
+            break;
+        };
+        if restructure_entries(cfg, &members, &mut counter) {
+            continue;
+        }
+        if restructure_exits(cfg, &members, &mut counter) {
+            continue;
+        }
+        // Neither pass found anything to fix, but the loop still shows up as
+        // a single SCC: nothing more to do for it.
+        break;
+    }
+}
+
+/// Find a loop (a non-trivial strongly connected component, or a
+/// single-node component with a self edge) that is still irreducible,
+/// i.e. has more than one distinct entry or exit.
+fn next_irreducible_loop(cfg: &Cfg) -> Option<HashSet<NodeIndex>> {
+    for scc in kosaraju_scc(&cfg.graph) {
+        let is_loop = scc.len() > 1
+            || cfg
+                .graph
+                .neighbors_directed(scc[0], Direction::Outgoing)
+                .any(|n| n == scc[0]);
+        if !is_loop {
+            continue;
+        }
+        let members: HashSet<NodeIndex> = scc.into_iter().collect();
+        if distinct_entries(cfg, &members).len() > 1 || distinct_exits(cfg, &members).len() > 1 {
+            return Some(members);
+        }
+    }
+    None
+}
+
+/// The distinct loop-member nodes entered from outside `members`, sorted by
+/// `NodeIndex` so that the selector values `restructure_entries` assigns -
+/// and therefore the synthesized block names - are deterministic across
+/// runs, since `members` itself is a `HashSet` with unspecified order.
+fn distinct_entries(cfg: &Cfg, members: &HashSet<NodeIndex>) -> Vec<NodeIndex> {
+    let mut entries = Vec::new();
+    for &node in members {
+        for src in cfg.graph.neighbors_directed(node, Direction::Incoming) {
+            if !members.contains(&src) && !entries.contains(&node) {
+                entries.push(node);
+            }
+        }
+    }
+    entries.sort();
+    entries
+}
+
+/// The distinct non-member nodes `members` exits to, sorted by `NodeIndex`
+/// for the same determinism reason as `distinct_entries`.
+fn distinct_exits(cfg: &Cfg, members: &HashSet<NodeIndex>) -> Vec<NodeIndex> {
+    let mut exits = Vec::new();
+    for &node in members {
+        for dst in cfg.graph.neighbors_directed(node, Direction::Outgoing) {
+            if !members.contains(&dst) && !exits.contains(&dst) {
+                exits.push(dst);
+            }
+        }
+    }
+    exits.sort();
+    exits
+}
+
+/// If `members` is entered at more than one of its blocks, synthesize a dispatch
+/// header that all external entries jump to, each carrying a distinct value
+/// of `ENTRY_SELECTOR`. Returns whether anything changed.
+fn restructure_entries(cfg: &mut Cfg, members: &HashSet<NodeIndex>, counter: &mut usize) -> bool {
+    let targets = distinct_entries(cfg, members);
+    if targets.len() <= 1 {
+        return false;
+    }
+
+    let header = cfg
+        .graph
+        .add_node(dispatch_block(counter, "entry.dispatch"));
+
+    // Redirect every edge entering one of `targets` - whether from outside
+    // the loop (a first-time entry) or from inside it (a back edge re-
+    // entering at a different node) - through a small block that records
+    // which target it was headed for, then jumps to `header`. Routing the
+    // back edges through `header` too is what makes `header` itself part of
+    // the loop, so it ends up as the loop's single true entry.
+    let redirect_edges: Vec<_> = targets
+        .iter()
+        .flat_map(|&node| {
+            cfg.graph
+                .edges_directed(node, Direction::Incoming)
+                .map(|e| e.id())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    for edge in redirect_edges {
+        let (src, dst) = cfg.graph.edge_endpoints(edge).unwrap();
+        let branch = cfg.graph.remove_edge(edge).unwrap();
+        let selector_index = targets.iter().position(|t| *t == dst).unwrap();
+        let setup = cfg.graph.add_node(set_selector_block(
+            counter,
+            "entry.setup",
+            ENTRY_SELECTOR,
+            selector_index,
+        ));
+        cfg.graph.add_edge(src, setup, branch);
+        cfg.graph.add_edge(
+            setup,
+            header,
+            Branch {
+                op: BranchOp::Jmp,
+                pos: None,
+            },
+        );
+    }
+
+    add_dispatch_chain(cfg, header, ENTRY_SELECTOR, &targets, counter);
+    true
+}
+
+/// If `members` is exited to more than one block, synthesize a dispatch
+/// block that all exits funnel through, each carrying a distinct value of
+/// `EXIT_SELECTOR`. Returns whether anything changed.
+fn restructure_exits(cfg: &mut Cfg, members: &HashSet<NodeIndex>, counter: &mut usize) -> bool {
+    let targets = distinct_exits(cfg, members);
+    if targets.len() <= 1 {
+        return false;
+    }
+
+    let dispatch = cfg.graph.add_node(dispatch_block(counter, "exit.dispatch"));
+
+    let internal_edges: Vec<_> = members
+        .iter()
+        .flat_map(|&node| {
+            cfg.graph
+                .edges_directed(node, Direction::Outgoing)
+                .filter(|e| !members.contains(&e.target()))
+                .map(|e| e.id())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    for edge in internal_edges {
+        let (src, dst) = cfg.graph.edge_endpoints(edge).unwrap();
+        let branch = cfg.graph.remove_edge(edge).unwrap();
+        let selector_index = targets.iter().position(|t| *t == dst).unwrap();
+        let setup = cfg.graph.add_node(set_selector_block(
+            counter,
+            "exit.setup",
+            EXIT_SELECTOR,
+            selector_index,
+        ));
+        cfg.graph.add_edge(src, setup, branch);
+        cfg.graph.add_edge(
+            setup,
+            dispatch,
+            Branch {
+                op: BranchOp::Jmp,
+                pos: None,
+            },
+        );
+    }
+
+    add_dispatch_chain(cfg, dispatch, EXIT_SELECTOR, &targets, counter);
+    true
+}
+
+fn dispatch_block(counter: &mut usize, prefix: &str) -> BasicBlock {
+    *counter += 1;
+    BasicBlock {
+        instrs: Vec::new(),
+        name: BlockName::Named(format!("{prefix}.{counter}")),
+        pos: None,
+    }
+}
+
+fn set_selector_block(
+    counter: &mut usize,
+    prefix: &str,
+    selector: &str,
+    value: usize,
+) -> BasicBlock {
+    *counter += 1;
+    BasicBlock {
+        instrs: vec![Instruction::Constant {
+            dest: selector.to_string(),
+            op: ConstOps::Const,
+            pos: None,
+            const_type: Type::Int,
+            value: Literal::Int(value as i64),
+        }],
+        name: BlockName::Named(format!("{prefix}.{counter}")),
+        pos: None,
+    }
+}
+
+/// Chain a series of equality tests off of `header`, one per entry in
+/// `targets`, each comparing `selector` against that target's index and
+/// branching straight to the target on a match. The last target is reached
+/// unconditionally, since by construction `selector` can't hold any other
+/// value.
+fn add_dispatch_chain(
+    cfg: &mut Cfg,
+    header: NodeIndex,
+    selector: &str,
+    targets: &[NodeIndex],
+    counter: &mut usize,
+) {
+    let mut current = header;
+    for (index, &target) in targets.iter().enumerate() {
+        if index == targets.len() - 1 {
+            cfg.graph.add_edge(
+                current,
+                target,
+                Branch {
+                    op: BranchOp::Jmp,
+                    pos: None,
+                },
+            );
+            break;
+        }
+        *counter += 1;
+        let test_var = format!("{selector}.eq.{counter}");
+        let idx_var = format!("{selector}.idx.{counter}");
+        let instrs = vec![
+            Instruction::Constant {
+                dest: idx_var.clone(),
+                op: ConstOps::Const,
+                pos: None,
+                const_type: Type::Int,
+                value: Literal::Int(index as i64),
+            },
+            Instruction::Value {
+                args: vec![selector.to_string(), idx_var],
+                dest: test_var.clone(),
+                funcs: Vec::new(),
+                labels: Vec::new(),
+                op: bril_rs::ValueOps::Eq,
+                pos: None,
+                op_type: Type::Bool,
+            },
+        ];
+        let next = if index == 0 {
+            // The first test can live directly in `header`.
+            cfg.graph.node_weight_mut(current).unwrap().instrs = instrs;
+            current
+        } else {
+            let cont = cfg.graph.add_node(BasicBlock {
+                instrs,
+                name: BlockName::Named(format!("{selector}.dispatch.{counter}")),
+                pos: None,
+            });
+            cfg.graph.add_edge(
+                current,
+                cont,
+                Branch {
+                    op: BranchOp::Jmp,
+                    pos: None,
+                },
+            );
+            cont
+        };
+        cfg.graph.add_edge(
+            next,
+            target,
+            Branch {
+                op: BranchOp::Cond {
+                    arg: test_var.clone(),
+                    val: true,
+                },
+                pos: None,
+            },
+        );
+        let fallthrough = cfg.graph.add_node(BasicBlock::empty(BlockName::Named(format!(
+            "{selector}.dispatch.{counter}.next"
+        ))));
+        cfg.graph.add_edge(
+            next,
+            fallthrough,
+            Branch {
+                op: BranchOp::Cond {
+                    arg: test_var,
+                    val: false,
+                },
+                pos: None,
+            },
+        );
+        current = fallthrough;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rvsdg::cfg::{BasicBlock, BlockName};
+
+    /// A loop with two distinct entries should come out with exactly one
+    /// block dominating all of the loop's nodes on entry.
+    #[test]
+    fn restructures_two_entry_loop() {
+        let mut graph = petgraph::Graph::new();
+        let pre_a = graph.add_node(BasicBlock::empty(BlockName::Named("pre_a".into())));
+        let pre_b = graph.add_node(BasicBlock::empty(BlockName::Named("pre_b".into())));
+        let head = graph.add_node(BasicBlock::empty(BlockName::Named("head".into())));
+        let body = graph.add_node(BasicBlock::empty(BlockName::Named("body".into())));
+        let exit = graph.add_node(BasicBlock::empty(BlockName::Exit));
+        graph.add_edge(
+            pre_a,
+            head,
+            Branch {
+                op: BranchOp::Jmp,
+                pos: None,
+            },
+        );
+        graph.add_edge(
+            pre_b,
+            body,
+            Branch {
+                op: BranchOp::Jmp,
+                pos: None,
+            },
+        );
+        graph.add_edge(
+            head,
+            body,
+            Branch {
+                op: BranchOp::Jmp,
+                pos: None,
+            },
+        );
+        graph.add_edge(
+            body,
+            head,
+            Branch {
+                op: BranchOp::Jmp,
+                pos: None,
+            },
+        );
+        graph.add_edge(
+            body,
+            exit,
+            Branch {
+                op: BranchOp::Jmp,
+                pos: None,
+            },
+        );
+        let mut cfg = Cfg {
+            args: Vec::new(),
+            graph,
+            entry: pre_a,
+            exit,
+        };
+        restructure(&mut cfg);
+        assert!(next_irreducible_loop(&cfg).is_none());
+    }
+}
@@ -0,0 +1,427 @@
+//! Single-entry/single-exit region detection via cycle equivalence.
+//!
+//! A gamma node in the target RVSDG corresponds to a single-entry,
+//! single-exit (SESE) region of the `Cfg`: control forks at the region's
+//! entry and every path through it rejoins at the region's exit before
+//! continuing. Finding *canonical* (maximal, non-overlapping except for
+//! nesting) SESE regions is the classic cycle-equivalence problem: two edges
+//! are cycle-equivalent iff every cycle through one also passes through the
+//! other. We compute this with the Johnson-Pearson-Pingali bracket-list
+//! algorithm, so that deeply nested conditionals get structured
+//! hierarchically instead of greedily (which tends to duplicate blocks).
+use std::collections::{HashMap, HashSet};
+
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use petgraph::visit::EdgeRef;
+
+use super::cfg::Cfg;
+
+/// An edge of the undirected multigraph used for cycle equivalence: a CFG
+/// edge together with a direction-erased identity so it can be compared
+/// regardless of which endpoint we reach it from during the DFS.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct UndirectedEdge(EdgeIndex);
+
+/// An open bracket on some node's bracket list: the back edge that created
+/// it, and the ancestor it climbs to (where it gets capped, i.e. removed
+/// from the list for good).
+#[derive(Clone, Copy)]
+struct Bracket {
+    edge: UndirectedEdge,
+    target: NodeIndex,
+}
+
+/// The cycle-equivalence class of every `Cfg` edge, including the synthetic
+/// back edge from `exit` to `entry`.
+pub(crate) struct RegionTree {
+    class_of: HashMap<EdgeIndex, usize>,
+}
+
+impl RegionTree {
+    /// Compute the cycle-equivalence class of every edge of `cfg`. Only the
+    /// classes are recorded: the regions they bound are not nested into a
+    /// program structure tree.
+    pub(crate) fn new(cfg: &Cfg) -> RegionTree {
+        CycleEquivalence::new(cfg).run()
+    }
+
+    /// The cycle-equivalence class of `edge`. Two edges with the same class
+    /// bound the same canonical SESE region.
+    pub(crate) fn class_of(&self, edge: EdgeIndex) -> usize {
+        self.class_of[&edge]
+    }
+
+    /// All edges that share `edge`'s cycle-equivalence class, i.e. the other
+    /// boundaries of the same canonical SESE region.
+    pub(crate) fn region_of(&self, edge: EdgeIndex) -> Vec<EdgeIndex> {
+        let class = self.class_of(edge);
+        self.class_of
+            .iter()
+            .filter(|(_, c)| **c == class)
+            .map(|(e, _)| *e)
+            .collect()
+    }
+}
+
+struct CycleEquivalence<'a> {
+    cfg: &'a Cfg,
+    /// Undirected adjacency for the DFS: for each node, the edges incident
+    /// to it (both the real directed `Cfg` edges and the synthetic
+    /// exit->entry back edge), each tagged with the neighbor reached
+    /// through it.
+    adj: HashMap<NodeIndex, Vec<(NodeIndex, UndirectedEdge)>>,
+    visited: HashMap<NodeIndex, bool>,
+    visited_edges: HashSet<UndirectedEdge>,
+    dfnum: HashMap<NodeIndex, usize>,
+    /// The lowest dfnum reachable from a node's subtree via at most one
+    /// back edge - the usual DFS "low-link".
+    hi: HashMap<NodeIndex, usize>,
+    counter: usize,
+    next_class: usize,
+    class_of: HashMap<EdgeIndex, usize>,
+    /// Bracket list per node, accumulated bottom-up as the DFS returns.
+    brackets: HashMap<NodeIndex, Vec<Bracket>>,
+    /// For a given back edge, the (size, class) a tree edge most recently
+    /// computed while that back edge was its bracket list's top bracket -
+    /// consecutive tree edges with the same top bracket and the same size
+    /// are cycle-equivalent and share a class.
+    recent_size: HashMap<UndirectedEdge, usize>,
+    recent_class: HashMap<UndirectedEdge, usize>,
+    /// The class a back edge's *sole* bracket tree edge was assigned - set
+    /// only when the back edge was the only bracket on that tree edge's
+    /// list. Per Johnson-Pearson-Pingali, a back edge is cycle-equivalent to
+    /// a tree edge exactly when it is that tree edge's one and only
+    /// bracket, so this (not `recent_class`, which is overwritten by any
+    /// tree edge the back edge merely sits on top of) is what a capped back
+    /// edge inherits its class from.
+    sole_bracket_class: HashMap<UndirectedEdge, usize>,
+}
+
+impl<'a> CycleEquivalence<'a> {
+    fn new(cfg: &'a Cfg) -> Self {
+        // We can't add a real petgraph edge without a `&mut Cfg`, but the
+        // algorithm only needs a synthetic identity to reason about the
+        // exit->entry edge, so we reserve an index past the real ones.
+        let synthetic = UndirectedEdge(EdgeIndex::new(cfg.graph.edge_count()));
+        let mut adj: HashMap<NodeIndex, Vec<(NodeIndex, UndirectedEdge)>> = HashMap::new();
+        for e in cfg.graph.edge_references() {
+            let ue = UndirectedEdge(e.id());
+            adj.entry(e.source()).or_default().push((e.target(), ue));
+            adj.entry(e.target()).or_default().push((e.source(), ue));
+        }
+        adj.entry(cfg.exit).or_default().push((cfg.entry, synthetic));
+        adj.entry(cfg.entry).or_default().push((cfg.exit, synthetic));
+
+        CycleEquivalence {
+            cfg,
+            adj,
+            visited: HashMap::new(),
+            visited_edges: HashSet::new(),
+            dfnum: HashMap::new(),
+            hi: HashMap::new(),
+            counter: 0,
+            next_class: 0,
+            class_of: HashMap::new(),
+            brackets: HashMap::new(),
+            recent_size: HashMap::new(),
+            recent_class: HashMap::new(),
+            sole_bracket_class: HashMap::new(),
+        }
+    }
+
+    fn run(mut self) -> RegionTree {
+        self.dfs(self.cfg.entry, None);
+        RegionTree {
+            class_of: self.class_of,
+        }
+    }
+
+    /// Depth-first walk assigning dfnums and bracket lists, computing each
+    /// tree edge's cycle-equivalence class on the way back up (postorder).
+    ///
+    /// Every edge is processed exactly once, from whichever endpoint
+    /// reaches it first: undirected DFS never produces cross edges, so an
+    /// edge to an already-visited node is always to an ancestor, regardless
+    /// of which side discovers it.
+    fn dfs(&mut self, node: NodeIndex, from: Option<(NodeIndex, UndirectedEdge)>) {
+        self.visited.insert(node, true);
+        self.counter += 1;
+        self.dfnum.insert(node, self.counter);
+        self.hi.insert(node, self.counter);
+        self.brackets.entry(node).or_default();
+
+        let neighbors = self.adj.get(&node).cloned().unwrap_or_default();
+        for (child, edge) in neighbors {
+            if self.visited_edges.contains(&edge) {
+                continue;
+            }
+            self.visited_edges.insert(edge);
+
+            if !*self.visited.get(&child).unwrap_or(&false) {
+                // Tree edge: recurse, then absorb the child's bracket list.
+                self.dfs(child, Some((node, edge)));
+                let child_hi = self.hi[&child];
+                if child_hi < self.hi[&node] {
+                    self.hi.insert(node, child_hi);
+                }
+                let child_brackets = self.brackets.remove(&child).unwrap_or_default();
+                self.brackets.get_mut(&node).unwrap().extend(child_brackets);
+            } else {
+                // Back edge, climbing from `node` up to the ancestor
+                // `child`: open a bracket that stays on `node`'s list (and
+                // whatever list absorbs it on the way up) until we reach
+                // `child` itself.
+                let child_dfnum = self.dfnum[&child];
+                if child_dfnum < self.hi[&node] {
+                    self.hi.insert(node, child_dfnum);
+                }
+                self.brackets
+                    .get_mut(&node)
+                    .unwrap()
+                    .push(Bracket { edge, target: child });
+            }
+        }
+
+        // Cap every bracket in our own list whose back edge's ancestor is
+        // exactly `node`: we've now returned to that ancestor, so the back
+        // edge can't bound any region further up the tree. A capped back
+        // edge is cycle-equivalent to a tree edge only when it was that
+        // tree edge's *sole* bracket (not merely its topmost one - a back
+        // edge can sit on top of several tree edges' lists on the way up
+        // without being cycle-equivalent to any of them), so it inherits
+        // `sole_bracket_class` if set, or gets a class all its own.
+        let capped: Vec<Bracket> = {
+            let list = self.brackets.get_mut(&node).unwrap();
+            let (stay, capped): (Vec<_>, Vec<_>) = list.drain(..).partition(|b| b.target != node);
+            *list = stay;
+            capped
+        };
+        for b in capped {
+            let class = match self.sole_bracket_class.get(&b.edge) {
+                Some(&c) => c,
+                None => self.fresh_class(),
+            };
+            self.class_of.insert(b.edge.0, class);
+        }
+
+        if let Some((parent, tree_edge)) = from {
+            self.assign_class(tree_edge, node, parent);
+        }
+    }
+
+    fn fresh_class(&mut self) -> usize {
+        let c = self.next_class;
+        self.next_class += 1;
+        c
+    }
+
+    /// Assign `tree_edge` a cycle-equivalence class from the size and
+    /// topmost bracket of `child`'s bracket list, per Johnson-Pearson-
+    /// Pingali: two tree edges are cycle-equivalent iff they have the same
+    /// size and the same topmost back edge.
+    fn assign_class(&mut self, tree_edge: UndirectedEdge, child: NodeIndex, _parent: NodeIndex) {
+        let brackets = self.brackets.get(&child).cloned().unwrap_or_default();
+        let size = brackets.len();
+
+        if size == 0 {
+            // Bridge: no back edge crosses this tree edge, so it can't be
+            // cycle-equivalent to anything else. `hi` corroborates this -
+            // no back edge from `child`'s subtree reaches `child` or
+            // higher, since if one did it would still be on the list.
+            debug_assert!(self.hi[&child] >= self.dfnum[&child]);
+            let class = self.fresh_class();
+            self.class_of.insert(tree_edge.0, class);
+            return;
+        }
+
+        let top = brackets.last().unwrap();
+        let class = if self.recent_size.get(&top.edge) == Some(&size) {
+            self.recent_class[&top.edge]
+        } else {
+            self.fresh_class()
+        };
+
+        if size == 1 {
+            // `top.edge` is this tree edge's one and only bracket, so the
+            // two are cycle-equivalent: record that for when `top.edge`
+            // itself gets capped.
+            self.sole_bracket_class.insert(top.edge, class);
+        }
+        self.recent_size.insert(top.edge, size);
+        self.recent_class.insert(top.edge, class);
+        self.class_of.insert(tree_edge.0, class);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rvsdg::cfg::{BasicBlock, BlockName, Branch, BranchOp};
+
+    fn jmp() -> Branch {
+        Branch {
+            op: BranchOp::Jmp,
+            pos: None,
+        }
+    }
+
+    /// entry -> a -> exit, with no branching: every edge is on the same
+    /// single path, so they should all land in the same class.
+    #[test]
+    fn straight_line_is_one_region() {
+        let mut graph = petgraph::Graph::new();
+        let entry = graph.add_node(BasicBlock::empty(BlockName::Entry));
+        let a = graph.add_node(BasicBlock::empty(BlockName::Named("a".into())));
+        let exit = graph.add_node(BasicBlock::empty(BlockName::Exit));
+        let e1 = graph.add_edge(entry, a, jmp());
+        let e2 = graph.add_edge(a, exit, jmp());
+        let cfg = Cfg {
+            args: Vec::new(),
+            graph,
+            entry,
+            exit,
+        };
+        let regions = RegionTree::new(&cfg);
+        assert_eq!(regions.class_of(e1), regions.class_of(e2));
+    }
+
+    /// entry branches on `cond` straight to `join` on one side and through
+    /// an extra block `a` on the other. The two edges of the `a` arm
+    /// (`entry -> a` and `a -> join`) lie on exactly the same cycles, so
+    /// they should be cycle-equivalent, while the direct `entry -> join`
+    /// branch and the `join -> exit` edge should not share that class.
+    #[test]
+    fn diamond_arm_edges_share_a_class() {
+        let mut graph = petgraph::Graph::new();
+        let entry = graph.add_node(BasicBlock::empty(BlockName::Entry));
+        let a = graph.add_node(BasicBlock::empty(BlockName::Named("a".into())));
+        let join = graph.add_node(BasicBlock::empty(BlockName::Named("join".into())));
+        let exit = graph.add_node(BasicBlock::empty(BlockName::Exit));
+        let e_true = graph.add_edge(
+            entry,
+            a,
+            Branch {
+                op: BranchOp::Cond {
+                    arg: "cond".into(),
+                    val: true,
+                },
+                pos: None,
+            },
+        );
+        let e_false = graph.add_edge(
+            entry,
+            join,
+            Branch {
+                op: BranchOp::Cond {
+                    arg: "cond".into(),
+                    val: false,
+                },
+                pos: None,
+            },
+        );
+        let e_a_join = graph.add_edge(a, join, jmp());
+        let e_join_exit = graph.add_edge(join, exit, jmp());
+        let cfg = Cfg {
+            args: Vec::new(),
+            graph,
+            entry,
+            exit,
+        };
+        let edge_count = cfg.graph.edge_count();
+        let regions = RegionTree::new(&cfg);
+        assert_eq!(regions.class_of(e_true), regions.class_of(e_a_join));
+        assert_ne!(regions.class_of(e_true), regions.class_of(e_false));
+        assert_ne!(regions.class_of(e_false), regions.class_of(e_join_exit));
+        // The synthetic exit->entry back edge is series-connected to
+        // `join -> exit` (both are incident to the degree-2 `exit`), so the
+        // two must share a class even though neither is adjacent to the
+        // if-then region at all.
+        let synthetic = EdgeIndex::new(edge_count);
+        assert_eq!(regions.class_of(synthetic), regions.class_of(e_join_exit));
+    }
+
+    /// A classic header-tested nested loop: `outer_head` tests whether to
+    /// enter the outer loop body (`inner_head`) or leave to `exit`;
+    /// `inner_head` tests whether to enter the inner loop body
+    /// (`inner_body`) or fall back to `outer_head`, completing the outer
+    /// loop's back edge; `inner_body` unconditionally closes the inner
+    /// loop's own back edge. Two back edges, the outer one sitting on top of
+    /// the inner one on `inner_head`'s bracket list during the walk, so a capped
+    /// back edge must only inherit a tree edge's class when it was that
+    /// tree edge's *sole* bracket - not merely its topmost one.
+    #[test]
+    fn nested_loops_are_distinct_regions() {
+        let mut graph = petgraph::Graph::new();
+        let entry = graph.add_node(BasicBlock::empty(BlockName::Entry));
+        let outer_head = graph.add_node(BasicBlock::empty(BlockName::Named("outer_head".into())));
+        let inner_head = graph.add_node(BasicBlock::empty(BlockName::Named("inner_head".into())));
+        let inner_body = graph.add_node(BasicBlock::empty(BlockName::Named("inner_body".into())));
+        let exit = graph.add_node(BasicBlock::empty(BlockName::Exit));
+        let e_enter = graph.add_edge(entry, outer_head, jmp());
+        let e_into_inner = graph.add_edge(
+            outer_head,
+            inner_head,
+            Branch {
+                op: BranchOp::Cond {
+                    arg: "outer.cond".into(),
+                    val: true,
+                },
+                pos: None,
+            },
+        );
+        let e_leave = graph.add_edge(
+            outer_head,
+            exit,
+            Branch {
+                op: BranchOp::Cond {
+                    arg: "outer.cond".into(),
+                    val: false,
+                },
+                pos: None,
+            },
+        );
+        let e_into_body = graph.add_edge(
+            inner_head,
+            inner_body,
+            Branch {
+                op: BranchOp::Cond {
+                    arg: "inner.cond".into(),
+                    val: true,
+                },
+                pos: None,
+            },
+        );
+        let e_outer_back = graph.add_edge(
+            inner_head,
+            outer_head,
+            Branch {
+                op: BranchOp::Cond {
+                    arg: "inner.cond".into(),
+                    val: false,
+                },
+                pos: None,
+            },
+        );
+        let e_inner_back = graph.add_edge(inner_body, inner_head, jmp());
+        let cfg = Cfg {
+            args: Vec::new(),
+            graph,
+            entry,
+            exit,
+        };
+        let regions = RegionTree::new(&cfg);
+        // The inner loop's own body-entry and back edges bound just the
+        // inner loop.
+        assert_eq!(regions.class_of(e_into_body), regions.class_of(e_inner_back));
+        // The outer loop's body-entry and back edges bound just the outer
+        // loop - distinct from the inner loop's region, even though
+        // `e_outer_back` sat on top of the inner back edge's bracket list
+        // partway through the walk.
+        assert_eq!(regions.class_of(e_into_inner), regions.class_of(e_outer_back));
+        assert_ne!(regions.class_of(e_into_body), regions.class_of(e_into_inner));
+        // Neither loop shares a class with the edges around both of them.
+        assert_ne!(regions.class_of(e_enter), regions.class_of(e_into_inner));
+        assert_ne!(regions.class_of(e_into_inner), regions.class_of(e_leave));
+        assert_eq!(regions.class_of(e_enter), regions.class_of(e_leave));
+    }
+}